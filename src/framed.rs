@@ -0,0 +1,245 @@
+//! Framed messages carrying a fixed 10-byte header in front of the usual
+//! bincode payload, so multiple logical streams can share one connection.
+//!
+//! The header is `length: u32, stream_id: u32, type_: u8, flags: u8`, all
+//! big-endian, analogous to a ttrpc-style `MessageHeader`. Callers pick a
+//! `stream_id` to route responses back to the request that triggered them,
+//! and a [`MessageType`] plus `flags` byte to describe the payload. The
+//! unframed `send_packet`/`recv_packet` path is unaffected and remains the
+//! simplest option when only one logical channel is needed.
+
+use std::{
+    error::Error,
+    io::{Read, Write},
+};
+
+use bincode::{Decode, Encode};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{PacketConfig, PacketError};
+
+/// Size in bytes of an encoded [`MessageHeader`].
+pub const HEADER_LEN: usize = 10;
+
+/// Discriminant carried in a [`MessageHeader`], describing the kind of
+/// payload that follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MessageType {
+    Request = 0,
+    Response = 1,
+    Data = 2,
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Result<Self, Box<dyn Error>> {
+        match value {
+            0 => Ok(MessageType::Request),
+            1 => Ok(MessageType::Response),
+            2 => Ok(MessageType::Data),
+            other => Err(Box::new(PacketError::UnknownMessageType(other))),
+        }
+    }
+}
+
+/// Set when the sender has closed its end of the stream identified by
+/// `stream_id`.
+pub const FLAG_REMOTE_CLOSED: u8 = 0b0000_0001;
+/// Set when the message carries no payload (an empty `length`).
+pub const FLAG_NO_DATA: u8 = 0b0000_0010;
+
+/// The fixed 10-byte header in front of every framed payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageHeader {
+    pub length: u32,
+    pub stream_id: u32,
+    pub type_: MessageType,
+    pub flags: u8,
+}
+
+impl MessageHeader {
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&self.length.to_be_bytes());
+        bytes[4..8].copy_from_slice(&self.stream_id.to_be_bytes());
+        bytes[8] = self.type_ as u8;
+        bytes[9] = self.flags;
+        bytes
+    }
+
+    pub fn from_bytes(bytes: [u8; HEADER_LEN]) -> Result<Self, Box<dyn Error>> {
+        let length = u32::from_be_bytes(bytes[0..4].try_into().unwrap());
+        let stream_id = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+        let type_ = MessageType::from_u8(bytes[8])?;
+        let flags = bytes[9];
+        Ok(Self {
+            length,
+            stream_id,
+            type_,
+            flags,
+        })
+    }
+}
+
+//
+// === SYNC ===
+//
+
+/// Encodes `packet` and writes it behind a [`MessageHeader`] carrying
+/// `stream_id`, `type_` and `flags`.
+pub fn send_framed<T: Encode, W: Write>(
+    packet: T,
+    stream_id: u32,
+    type_: MessageType,
+    flags: u8,
+    writer: &mut W,
+) -> Result<(), Box<dyn Error>> {
+    let encoded_packet = bincode::encode_to_vec(&packet, bincode::config::standard())?;
+    let header = MessageHeader {
+        length: encoded_packet.len() as u32,
+        stream_id,
+        type_,
+        flags,
+    };
+    writer.write_all(&header.to_bytes())?;
+    writer.write_all(&encoded_packet)?;
+    Ok(())
+}
+
+/// Reads a framed message using [`PacketConfig::default`]. See
+/// [`recv_framed_with`] for a version that accepts a custom size limit.
+pub fn recv_framed<T: Decode<()>, R: Read>(
+    reader: &mut R,
+) -> Result<(MessageHeader, T), Box<dyn Error>> {
+    recv_framed_with(reader, PacketConfig::default())
+}
+
+/// Reads a framed message, rejecting it with [`PacketError::PacketTooLarge`]
+/// before allocating if the header's `length` exceeds `config.max_len`.
+pub fn recv_framed_with<T: Decode<()>, R: Read>(
+    reader: &mut R,
+    config: PacketConfig,
+) -> Result<(MessageHeader, T), Box<dyn Error>> {
+    let mut header_bytes = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header_bytes)?;
+    let header = MessageHeader::from_bytes(header_bytes)?;
+
+    let len = header.length as usize;
+    if len > config.max_len {
+        return Err(Box::new(PacketError::PacketTooLarge {
+            len,
+            max: config.max_len,
+        }));
+    }
+
+    let mut packet = vec![0; len];
+    reader.read_exact(&mut packet)?;
+
+    let (decoded, _) = bincode::decode_from_slice(&packet, bincode::config::standard())?;
+    Ok((header, decoded))
+}
+
+//
+// === ASYNC ===
+//
+
+pub async fn send_framed_async<T: Encode, W: AsyncWrite + Unpin>(
+    packet: T,
+    stream_id: u32,
+    type_: MessageType,
+    flags: u8,
+    writer: &mut W,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let encoded_packet = bincode::encode_to_vec(&packet, bincode::config::standard())?;
+    let header = MessageHeader {
+        length: encoded_packet.len() as u32,
+        stream_id,
+        type_,
+        flags,
+    };
+    writer.write_all(&header.to_bytes()).await?;
+    writer.write_all(&encoded_packet).await?;
+    Ok(())
+}
+
+/// Reads a framed message using [`PacketConfig::default`]. See
+/// [`recv_framed_async_with`] for a version that accepts a custom size limit.
+pub async fn recv_framed_async<T: Decode<()>, R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<(MessageHeader, T), Box<dyn Error + Send + Sync>> {
+    recv_framed_async_with(reader, PacketConfig::default()).await
+}
+
+/// Reads a framed message, rejecting it with [`PacketError::PacketTooLarge`]
+/// before allocating if the header's `length` exceeds `config.max_len`.
+pub async fn recv_framed_async_with<T: Decode<()>, R: AsyncRead + Unpin>(
+    reader: &mut R,
+    config: PacketConfig,
+) -> Result<(MessageHeader, T), Box<dyn Error + Send + Sync>> {
+    let mut header_bytes = [0u8; HEADER_LEN];
+    reader.read_exact(&mut header_bytes).await?;
+    let header = MessageHeader::from_bytes(header_bytes).map_err(|e| -> Box<dyn Error + Send + Sync> {
+        format!("{e}").into()
+    })?;
+
+    let len = header.length as usize;
+    if len > config.max_len {
+        return Err(Box::new(PacketError::PacketTooLarge {
+            len,
+            max: config.max_len,
+        }));
+    }
+
+    let mut packet = vec![0; len];
+    reader.read_exact(&mut packet).await?;
+
+    let (decoded, _) = bincode::decode_from_slice(&packet, bincode::config::standard())?;
+    Ok((header, decoded))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use os_pipe::pipe;
+
+    #[derive(Encode, Decode, Debug, PartialEq)]
+    struct TestStruct {
+        field1: u8,
+        field2: u16,
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = MessageHeader {
+            length: 42,
+            stream_id: 7,
+            type_: MessageType::Data,
+            flags: FLAG_NO_DATA,
+        };
+
+        let bytes = header.to_bytes();
+        let decoded = MessageHeader::from_bytes(bytes).unwrap();
+
+        assert_eq!(header, decoded);
+    }
+
+    #[test]
+    fn test_send_recv_framed_round_trip() {
+        let (mut reader, mut writer) = pipe().unwrap();
+
+        let test_struct = TestStruct {
+            field1: 1,
+            field2: 2,
+        };
+
+        send_framed(&test_struct, 3, MessageType::Request, FLAG_REMOTE_CLOSED, &mut writer).unwrap();
+        drop(writer);
+
+        let (header, result): (MessageHeader, TestStruct) = recv_framed(&mut reader).unwrap();
+
+        assert_eq!(header.stream_id, 3);
+        assert_eq!(header.type_, MessageType::Request);
+        assert_eq!(header.flags, FLAG_REMOTE_CLOSED);
+        assert_eq!(result, test_struct);
+    }
+}
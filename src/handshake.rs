@@ -0,0 +1,275 @@
+//! A version-negotiation handshake to run before the regular
+//! `send_packet`/`recv_packet` loop.
+//!
+//! Two ends of a long-lived connection exchange a small fixed greeting —
+//! a magic tag, a protocol version byte, and an optional 32-byte
+//! identity/auth digest (e.g. a SHA-256 of a shared token) — so an
+//! incompatible or unauthenticated peer is rejected before any real packets
+//! are sent.
+//!
+//! Wire format per greeting: `magic: [u8; 4] || version: u8 || has_digest: u8
+//! || digest: [u8; 32] if has_digest == 1`.
+
+use std::{
+    error::Error,
+    io::{Read, Write},
+};
+
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::PacketError;
+
+/// Magic tag identifying a `packetio` handshake greeting.
+pub const PROTO_MAGIC: [u8; 4] = *b"PKIO";
+
+/// The protocol version this build of `packetio` speaks.
+pub const CURRENT_PROTO_VERSION: u8 = 1;
+
+struct Greeting {
+    version: u8,
+    auth_digest: Option<[u8; 32]>,
+}
+
+/// Compares an optional peer digest against the one we expect in constant
+/// time, so a network-adjacent attacker can't use response timing to
+/// recover the expected digest byte-by-byte.
+fn digest_matches(peer_digest: Option<[u8; 32]>, expected: &[u8; 32]) -> bool {
+    match peer_digest {
+        Some(digest) => digest[..].ct_eq(&expected[..]).into(),
+        None => false,
+    }
+}
+
+fn write_greeting<W: Write>(writer: &mut W, greeting: &Greeting) -> Result<(), Box<dyn Error>> {
+    writer.write_all(&PROTO_MAGIC)?;
+    writer.write_all(&[greeting.version])?;
+    match greeting.auth_digest {
+        Some(digest) => {
+            writer.write_all(&[1])?;
+            writer.write_all(&digest)?;
+        }
+        None => writer.write_all(&[0])?,
+    }
+    Ok(())
+}
+
+fn read_greeting<R: Read>(reader: &mut R) -> Result<Greeting, Box<dyn Error>> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != PROTO_MAGIC {
+        return Err(Box::new(PacketError::BadMagic));
+    }
+
+    let mut version = [0u8; 1];
+    reader.read_exact(&mut version)?;
+
+    let mut has_digest = [0u8; 1];
+    reader.read_exact(&mut has_digest)?;
+
+    let auth_digest = if has_digest[0] == 1 {
+        let mut digest = [0u8; 32];
+        reader.read_exact(&mut digest)?;
+        Some(digest)
+    } else {
+        None
+    };
+
+    Ok(Greeting {
+        version: version[0],
+        auth_digest,
+    })
+}
+
+/// Writes our greeting, reads the peer's, and returns the negotiated
+/// protocol version.
+///
+/// `our_auth_digest` is sent to the peer as our identity/auth digest, if
+/// any. `expected_peer_digest`, if set, must match the peer's digest or
+/// this returns [`PacketError::Unauthorized`]. A peer speaking a different
+/// [`CURRENT_PROTO_VERSION`] results in [`PacketError::VersionMismatch`].
+pub fn handshake<S: Read + Write>(
+    stream: &mut S,
+    our_auth_digest: Option<[u8; 32]>,
+    expected_peer_digest: Option<[u8; 32]>,
+) -> Result<u8, Box<dyn Error>> {
+    write_greeting(
+        stream,
+        &Greeting {
+            version: CURRENT_PROTO_VERSION,
+            auth_digest: our_auth_digest,
+        },
+    )?;
+
+    let peer = read_greeting(stream)?;
+
+    if let Some(expected) = expected_peer_digest {
+        if !digest_matches(peer.auth_digest, &expected) {
+            return Err(Box::new(PacketError::Unauthorized));
+        }
+    }
+
+    if peer.version != CURRENT_PROTO_VERSION {
+        return Err(Box::new(PacketError::VersionMismatch {
+            ours: CURRENT_PROTO_VERSION,
+            theirs: peer.version,
+        }));
+    }
+
+    Ok(peer.version)
+}
+
+async fn write_greeting_async<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    greeting: &Greeting,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    writer.write_all(&PROTO_MAGIC).await?;
+    writer.write_all(&[greeting.version]).await?;
+    match greeting.auth_digest {
+        Some(digest) => {
+            writer.write_all(&[1]).await?;
+            writer.write_all(&digest).await?;
+        }
+        None => writer.write_all(&[0]).await?,
+    }
+    Ok(())
+}
+
+async fn read_greeting_async<R: AsyncRead + Unpin>(
+    reader: &mut R,
+) -> Result<Greeting, Box<dyn Error + Send + Sync>> {
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic).await?;
+    if magic != PROTO_MAGIC {
+        return Err(Box::new(PacketError::BadMagic));
+    }
+
+    let mut version = [0u8; 1];
+    reader.read_exact(&mut version).await?;
+
+    let mut has_digest = [0u8; 1];
+    reader.read_exact(&mut has_digest).await?;
+
+    let auth_digest = if has_digest[0] == 1 {
+        let mut digest = [0u8; 32];
+        reader.read_exact(&mut digest).await?;
+        Some(digest)
+    } else {
+        None
+    };
+
+    Ok(Greeting {
+        version: version[0],
+        auth_digest,
+    })
+}
+
+/// Async counterpart to [`handshake`]. See its docs for the wire format and
+/// error conditions.
+pub async fn handshake_async<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    our_auth_digest: Option<[u8; 32]>,
+    expected_peer_digest: Option<[u8; 32]>,
+) -> Result<u8, Box<dyn Error + Send + Sync>> {
+    write_greeting_async(
+        stream,
+        &Greeting {
+            version: CURRENT_PROTO_VERSION,
+            auth_digest: our_auth_digest,
+        },
+    )
+    .await?;
+
+    let peer = read_greeting_async(stream).await?;
+
+    if let Some(expected) = expected_peer_digest {
+        if !digest_matches(peer.auth_digest, &expected) {
+            return Err(Box::new(PacketError::Unauthorized));
+        }
+    }
+
+    if peer.version != CURRENT_PROTO_VERSION {
+        return Err(Box::new(PacketError::VersionMismatch {
+            ours: CURRENT_PROTO_VERSION,
+            theirs: peer.version,
+        }));
+    }
+
+    Ok(peer.version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use os_pipe::pipe;
+
+    struct PairedStream<R, W> {
+        reader: R,
+        writer: W,
+    }
+
+    impl<R: Read, W: Write> Read for PairedStream<R, W> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.reader.read(buf)
+        }
+    }
+
+    impl<R: Read, W: Write> Write for PairedStream<R, W> {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.writer.write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.writer.flush()
+        }
+    }
+
+    #[test]
+    fn test_handshake_round_trip_over_loopback_pair() {
+        let (a_reader, b_writer) = pipe().unwrap();
+        let (b_reader, a_writer) = pipe().unwrap();
+
+        let mut a = PairedStream {
+            reader: a_reader,
+            writer: a_writer,
+        };
+        let mut b = PairedStream {
+            reader: b_reader,
+            writer: b_writer,
+        };
+
+        // `Box<dyn Error>` isn't `Send`, so map it to a `Send` type before it
+        // crosses the thread boundary.
+        let handle = std::thread::spawn(move || handshake(&mut b, None, None).map_err(|e| e.to_string()));
+
+        let ours = handshake(&mut a, None, None).unwrap();
+        let theirs = handle.join().unwrap().unwrap();
+
+        assert_eq!(ours, CURRENT_PROTO_VERSION);
+        assert_eq!(theirs, CURRENT_PROTO_VERSION);
+    }
+
+    #[test]
+    fn test_handshake_rejects_mismatched_auth_digest() {
+        let (a_reader, b_writer) = pipe().unwrap();
+        let (b_reader, a_writer) = pipe().unwrap();
+
+        let mut a = PairedStream {
+            reader: a_reader,
+            writer: a_writer,
+        };
+        let mut b = PairedStream {
+            reader: b_reader,
+            writer: b_writer,
+        };
+
+        let handle =
+            std::thread::spawn(move || handshake(&mut b, Some([1u8; 32]), None).map_err(|e| e.to_string()));
+
+        let result = handshake(&mut a, None, Some([2u8; 32]));
+        let _ = handle.join().unwrap();
+
+        let err = result.unwrap_err();
+        let packet_err = err.downcast_ref::<PacketError>().expect("expected PacketError");
+        assert!(matches!(packet_err, PacketError::Unauthorized));
+    }
+}
@@ -28,11 +28,288 @@
 
 use std::{
     error::Error,
+    fmt,
     io::{Read, Write},
 };
 
 use bincode::{Decode, Encode};
 
+pub mod framed;
+pub use framed::{MessageHeader, MessageType, recv_framed, recv_framed_async, send_framed, send_framed_async};
+
+#[cfg(feature = "encryption")]
+pub mod encryption;
+#[cfg(feature = "encryption")]
+pub use encryption::SecureStream;
+
+pub mod handshake;
+pub use handshake::{handshake, handshake_async, CURRENT_PROTO_VERSION};
+
+pub mod multiplexer;
+pub use multiplexer::{Multiplexer, Priority, RequestId, MAX_CHUNK_SIZE};
+
+pub mod macros;
+
+pub mod streaming;
+pub use streaming::{AsyncPacketReader, AsyncPacketWriter, PacketReader, PacketWriter};
+
+//
+// === CONFIG ===
+//
+
+/// Default ceiling on a single packet's decoded length: 4 MiB.
+///
+/// This mirrors the kind of wire-size limit most length-prefixed protocols
+/// settle on: generous enough for real payloads, small enough that a bogus
+/// or malicious length prefix can't force a multi-gigabyte allocation.
+pub const DEFAULT_MAX_PACKET_LEN: usize = 4 * 1024 * 1024;
+
+/// Default threshold, in encoded bytes, above which `_with` senders compress
+/// the payload when `compression` is not [`Compression::None`].
+pub const DEFAULT_COMPRESS_THRESHOLD: usize = 8 * 1024;
+
+/// Compression algorithm applied to a packet's encoded body before it is
+/// written to the wire.
+///
+/// Selected via [`PacketConfig::compression`]; a one-byte marker identifying
+/// the algorithm used for *that* packet is written in front of the payload
+/// by the `_with` senders, so small packets stored uncompressed cost nothing
+/// beyond that marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None = 0,
+    Deflate = 1,
+    Zstd = 2,
+}
+
+impl Compression {
+    fn marker(self) -> u8 {
+        self as u8
+    }
+
+    fn from_marker(marker: u8) -> Result<Self, Box<dyn Error>> {
+        match marker {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Deflate),
+            2 => Ok(Compression::Zstd),
+            other => Err(Box::new(PacketError::UnknownCompressionMarker(other))),
+        }
+    }
+}
+
+/// Configuration for the `_with` packet send/receive variants.
+///
+/// `recv_packet`/`recv_packet_async` do not take a config: they apply the
+/// default size limit ([`DEFAULT_MAX_PACKET_LEN`]) and read a body with no
+/// compression marker byte. Use the `_with` variants together with a custom
+/// config to raise or lower the size limit, or to opt into compression; their
+/// bodies always carry the marker byte, even with [`PacketConfig::default`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketConfig {
+    /// Maximum allowed decoded packet length, in bytes.
+    pub max_len: usize,
+    /// Compression algorithm to use when sending, if the encoded payload
+    /// exceeds `compress_threshold`. Ignored on receive, which always reads
+    /// the per-packet marker byte and decompresses accordingly.
+    pub compression: Compression,
+    /// Encoded-payload size, in bytes, above which `compression` is applied.
+    pub compress_threshold: usize,
+}
+
+impl Default for PacketConfig {
+    fn default() -> Self {
+        Self {
+            max_len: DEFAULT_MAX_PACKET_LEN,
+            compression: Compression::None,
+            compress_threshold: DEFAULT_COMPRESS_THRESHOLD,
+        }
+    }
+}
+
+impl PacketConfig {
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_compression(mut self, compression: Compression, compress_threshold: usize) -> Self {
+        self.compression = compression;
+        self.compress_threshold = compress_threshold;
+        self
+    }
+}
+
+//
+// === ERRORS ===
+//
+
+/// Errors raised by `packetio` itself, as opposed to I/O or decode errors
+/// from the underlying stream/`bincode`.
+#[derive(Debug)]
+pub enum PacketError {
+    /// The peer's length prefix exceeded the configured [`PacketConfig::max_len`].
+    ///
+    /// Returned *before* allocating a buffer for the packet body, so a
+    /// malicious or corrupt length prefix can't be used to force an
+    /// oversized allocation.
+    PacketTooLarge { len: usize, max: usize },
+
+    /// A framed message header named an unrecognized `type_` discriminant.
+    UnknownMessageType(u8),
+
+    /// A packet's compression marker byte did not match a known [`Compression`] variant.
+    UnknownCompressionMarker(u8),
+
+    /// A packet's compressed body decompressed to more than the configured
+    /// [`PacketConfig::max_len`].
+    ///
+    /// Returned as soon as decompression crosses the cap, so a peer can't
+    /// use a small compressed body to force an unbounded allocation while
+    /// decompressing.
+    DecompressedPacketTooLarge { max: usize },
+
+    /// AEAD tag verification failed while decrypting a [`encryption::SecureStream`] packet,
+    /// meaning the ciphertext was tampered with, corrupted, or encrypted under a different key.
+    #[cfg(feature = "encryption")]
+    Decryption,
+
+    /// The peer's handshake greeting did not start with the expected magic tag.
+    BadMagic,
+
+    /// The peer negotiated a different protocol version than ours.
+    VersionMismatch { ours: u8, theirs: u8 },
+
+    /// The peer's handshake auth digest did not match the one we expected.
+    Unauthorized,
+
+    /// A chunk frame named an unrecognized [`multiplexer::Priority`] discriminant.
+    UnknownPriority(u8),
+
+    /// A [`protocol_state!`]-generated dispatcher received a packet id with no matching variant.
+    UnknownPacketId(u8),
+
+    /// A [`streaming::PacketReader`] (or its async counterpart) was dropped
+    /// via [`streaming::PacketReader::finish`] before the caller read all of
+    /// the declared packet body, which would leave the stream misaligned
+    /// for the next packet.
+    IncompleteRead { remaining: usize },
+
+    /// A [`streaming::PacketWriter`] (or its async counterpart) was finished
+    /// without writing exactly the number of bytes declared up front.
+    IncompleteWrite { written: usize, declared: usize },
+
+    /// A [`streaming::PacketWriter`] (or its async counterpart) was
+    /// constructed with a declared length that doesn't fit in the `u32`
+    /// wire length prefix.
+    DeclaredLenTooLarge { declared_len: usize },
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::PacketTooLarge { len, max } => write!(
+                f,
+                "packet length {len} exceeds configured maximum {max}"
+            ),
+            PacketError::UnknownMessageType(type_) => {
+                write!(f, "unknown message type byte {type_}")
+            }
+            PacketError::UnknownCompressionMarker(marker) => {
+                write!(f, "unknown compression marker byte {marker}")
+            }
+            PacketError::DecompressedPacketTooLarge { max } => write!(
+                f,
+                "decompressed packet exceeds configured maximum {max}"
+            ),
+            #[cfg(feature = "encryption")]
+            PacketError::Decryption => write!(f, "AEAD tag verification failed while decrypting packet"),
+            PacketError::BadMagic => write!(f, "peer greeting did not start with the expected magic tag"),
+            PacketError::VersionMismatch { ours, theirs } => write!(
+                f,
+                "protocol version mismatch: we are {ours}, peer is {theirs}"
+            ),
+            PacketError::Unauthorized => write!(f, "peer's handshake auth digest did not match"),
+            PacketError::UnknownPriority(priority) => write!(f, "unknown chunk priority byte {priority}"),
+            PacketError::UnknownPacketId(id) => write!(f, "unknown packet id byte {id}"),
+            PacketError::IncompleteRead { remaining } => write!(
+                f,
+                "{remaining} byte(s) of the packet body were left unread"
+            ),
+            PacketError::IncompleteWrite { written, declared } => write!(
+                f,
+                "only {written} of {declared} declared byte(s) were written"
+            ),
+            PacketError::DeclaredLenTooLarge { declared_len } => write!(
+                f,
+                "declared packet length {declared_len} exceeds the u32 wire length prefix"
+            ),
+        }
+    }
+}
+
+fn compress_body(encoded: &[u8], config: &PacketConfig) -> Result<Vec<u8>, Box<dyn Error>> {
+    if config.compression == Compression::None || encoded.len() <= config.compress_threshold {
+        let mut body = Vec::with_capacity(1 + encoded.len());
+        body.push(Compression::None.marker());
+        body.extend_from_slice(encoded);
+        return Ok(body);
+    }
+
+    let compressed = match config.compression {
+        Compression::Deflate => {
+            use flate2::{write::DeflateEncoder, Compression as Flate2Level};
+            let mut encoder = DeflateEncoder::new(Vec::new(), Flate2Level::default());
+            encoder.write_all(encoded)?;
+            encoder.finish()?
+        }
+        Compression::Zstd => zstd::encode_all(encoded, 0)?,
+        Compression::None => unreachable!(),
+    };
+
+    let mut body = Vec::with_capacity(1 + compressed.len());
+    body.push(config.compression.marker());
+    body.extend_from_slice(&compressed);
+    Ok(body)
+}
+
+/// Reads `decoder` to end through a `max_len + 1`-byte cap, so a decoder
+/// that would expand its input past `max_len` is cut off instead of
+/// materializing an unbounded `Vec`. Returns
+/// [`PacketError::DecompressedPacketTooLarge`] once the cap is crossed.
+fn read_decompressed_bounded<R: Read>(decoder: R, max_len: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut out = Vec::new();
+    decoder.take(max_len as u64 + 1).read_to_end(&mut out)?;
+
+    if out.len() > max_len {
+        return Err(Box::new(PacketError::DecompressedPacketTooLarge { max: max_len }));
+    }
+    Ok(out)
+}
+
+fn decompress_body(body: &[u8], max_len: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let (marker, payload) = body
+        .split_first()
+        .ok_or_else(|| -> Box<dyn Error> { "empty packet body".into() })?;
+
+    match Compression::from_marker(*marker)? {
+        Compression::None => {
+            if payload.len() > max_len {
+                return Err(Box::new(PacketError::DecompressedPacketTooLarge { max: max_len }));
+            }
+            Ok(payload.to_vec())
+        }
+        Compression::Deflate => {
+            use flate2::read::DeflateDecoder;
+            read_decompressed_bounded(DeflateDecoder::new(payload), max_len)
+        }
+        Compression::Zstd => read_decompressed_bounded(zstd::stream::read::Decoder::new(payload)?, max_len),
+    }
+}
+
+impl Error for PacketError {}
+
 //
 // === SYNC TRAITS ===
 //
@@ -42,6 +319,15 @@ pub trait PacketSender {
 
 pub trait PacketReceiver {
     fn recv_packet<T: Decode<()>>(&mut self) -> Result<T, Box<dyn Error>>;
+
+    /// Like [`PacketReceiver::recv_packet`], but rejects length prefixes
+    /// larger than `config.max_len` before allocating the packet buffer, and
+    /// expects the leading compression marker byte written by
+    /// [`send_packet_with`].
+    fn recv_packet_with<T: Decode<()>>(
+        &mut self,
+        config: PacketConfig,
+    ) -> Result<T, Box<dyn Error>>;
 }
 
 impl<T: Write> PacketSender for T {
@@ -54,6 +340,13 @@ impl<T: Read> PacketReceiver for T {
     fn recv_packet<U: Decode<()>>(&mut self) -> Result<U, Box<dyn Error>> {
         recv_packet(self)
     }
+
+    fn recv_packet_with<U: Decode<()>>(
+        &mut self,
+        config: PacketConfig,
+    ) -> Result<U, Box<dyn Error>> {
+        recv_packet_with(self, config)
+    }
 }
 
 pub fn send_packet<T: Encode, W: Write>(packet: T, writer: &mut W) -> Result<(), Box<dyn Error>> {
@@ -63,14 +356,69 @@ pub fn send_packet<T: Encode, W: Write>(packet: T, writer: &mut W) -> Result<(),
     Ok(())
 }
 
+/// Encodes and writes `packet` using `config`, compressing the encoded body
+/// first if `config.compression` is set and the body exceeds
+/// `config.compress_threshold`. Pair with [`recv_packet_with`], which always
+/// expects the leading compression marker byte this writes.
+pub fn send_packet_with<T: Encode, W: Write>(
+    packet: T,
+    writer: &mut W,
+    config: PacketConfig,
+) -> Result<(), Box<dyn Error>> {
+    let encoded_packet = bincode::encode_to_vec(&packet, bincode::config::standard())?;
+    let body = compress_body(&encoded_packet, &config)?;
+    writer.write_all(&(body.len() as u32).to_be_bytes())?;
+    writer.write_all(&body)?;
+    Ok(())
+}
+
+/// Reads a packet written by [`send_packet`], rejecting it with
+/// [`PacketError::PacketTooLarge`] before allocating if the prefixed length
+/// exceeds [`DEFAULT_MAX_PACKET_LEN`]. See [`recv_packet_with`] for a version
+/// that accepts a custom size limit and pairs with [`send_packet_with`]'s
+/// compressed framing.
 pub fn recv_packet<T: Decode<()>, R: Read>(reader: &mut R) -> Result<T, Box<dyn Error>> {
     let mut len_bytes = [0; 4];
     reader.read_exact(&mut len_bytes)?;
     let len = u32::from_be_bytes(len_bytes) as usize;
 
-    let mut packet = vec![0; len];
-    reader.read_exact(&mut packet)?;
+    if len > DEFAULT_MAX_PACKET_LEN {
+        return Err(Box::new(PacketError::PacketTooLarge {
+            len,
+            max: DEFAULT_MAX_PACKET_LEN,
+        }));
+    }
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
 
+    let (decoded, _) = bincode::decode_from_slice(&body, bincode::config::standard())?;
+    Ok(decoded)
+}
+
+/// Reads a packet written by [`send_packet_with`], rejecting it with
+/// [`PacketError::PacketTooLarge`] before allocating if the prefixed length
+/// exceeds `config.max_len`, then decompressing the body according to its
+/// leading marker byte.
+pub fn recv_packet_with<T: Decode<()>, R: Read>(
+    reader: &mut R,
+    config: PacketConfig,
+) -> Result<T, Box<dyn Error>> {
+    let mut len_bytes = [0; 4];
+    reader.read_exact(&mut len_bytes)?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+
+    if len > config.max_len {
+        return Err(Box::new(PacketError::PacketTooLarge {
+            len,
+            max: config.max_len,
+        }));
+    }
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body)?;
+
+    let packet = decompress_body(&body, config.max_len)?;
     let (decoded, _) = bincode::decode_from_slice(&packet, bincode::config::standard())?;
     Ok(decoded)
 }
@@ -88,6 +436,15 @@ pub trait AsyncPacketSender {
 #[async_trait::async_trait]
 pub trait AsyncPacketReceiver {
     async fn recv_packet_async<T: Decode<()> + Send>(&mut self) -> Result<T, Box<dyn Error + Send + Sync>>;
+
+    /// Like [`AsyncPacketReceiver::recv_packet_async`], but rejects length
+    /// prefixes larger than `config.max_len` before allocating the packet
+    /// buffer, and expects the leading compression marker byte written by
+    /// [`send_packet_async_with`].
+    async fn recv_packet_async_with<T: Decode<()> + Send>(
+        &mut self,
+        config: PacketConfig,
+    ) -> Result<T, Box<dyn Error + Send + Sync>>;
 }
 
 #[async_trait::async_trait]
@@ -102,6 +459,13 @@ impl<T: AsyncRead + Unpin + Send> AsyncPacketReceiver for T {
     async fn recv_packet_async<U: Decode<()> + Send>(&mut self) -> Result<U, Box<dyn Error + Send + Sync>> {
         recv_packet_async(self).await
     }
+
+    async fn recv_packet_async_with<U: Decode<()> + Send>(
+        &mut self,
+        config: PacketConfig,
+    ) -> Result<U, Box<dyn Error + Send + Sync>> {
+        recv_packet_async_with(self, config).await
+    }
 }
 
 pub async fn send_packet_async<T: Encode, W: AsyncWrite + Unpin>(
@@ -114,6 +478,22 @@ pub async fn send_packet_async<T: Encode, W: AsyncWrite + Unpin>(
     Ok(())
 }
 
+/// Async counterpart to [`send_packet_with`]. See its docs for the wire
+/// format and how `config` affects compression.
+pub async fn send_packet_async_with<T: Encode, W: AsyncWrite + Unpin>(
+    packet: T,
+    writer: &mut W,
+    config: PacketConfig,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let encoded_packet = bincode::encode_to_vec(&packet, bincode::config::standard())?;
+    let body = compress_body(&encoded_packet, &config).map_err(|e| -> Box<dyn Error + Send + Sync> { format!("{e}").into() })?;
+    writer.write_all(&(body.len() as u32).to_be_bytes()).await?;
+    writer.write_all(&body).await?;
+    Ok(())
+}
+
+/// Async counterpart to [`recv_packet`]. See its docs for the wire format
+/// and how it pairs with [`send_packet_async`].
 pub async fn recv_packet_async<T: Decode<()>, R: AsyncRead + Unpin>(
     reader: &mut R
 ) -> Result<T, Box<dyn Error + Send + Sync>> {
@@ -121,9 +501,42 @@ pub async fn recv_packet_async<T: Decode<()>, R: AsyncRead + Unpin>(
     reader.read_exact(&mut len_bytes).await?;
     let len = u32::from_be_bytes(len_bytes) as usize;
 
-    let mut packet = vec![0; len];
-    reader.read_exact(&mut packet).await?;
+    if len > DEFAULT_MAX_PACKET_LEN {
+        return Err(Box::new(PacketError::PacketTooLarge {
+            len,
+            max: DEFAULT_MAX_PACKET_LEN,
+        }));
+    }
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
 
+    let (decoded, _) = bincode::decode_from_slice(&body, bincode::config::standard())?;
+    Ok(decoded)
+}
+
+/// Async counterpart to [`recv_packet_with`]. See its docs for the wire
+/// format and how `config` affects the size limit and decompression.
+pub async fn recv_packet_async_with<T: Decode<()>, R: AsyncRead + Unpin>(
+    reader: &mut R,
+    config: PacketConfig,
+) -> Result<T, Box<dyn Error + Send + Sync>> {
+    let mut len_bytes = [0; 4];
+    reader.read_exact(&mut len_bytes).await?;
+    let len = u32::from_be_bytes(len_bytes) as usize;
+
+    if len > config.max_len {
+        return Err(Box::new(PacketError::PacketTooLarge {
+            len,
+            max: config.max_len,
+        }));
+    }
+
+    let mut body = vec![0; len];
+    reader.read_exact(&mut body).await?;
+
+    let packet = decompress_body(&body, config.max_len)
+        .map_err(|e| -> Box<dyn Error + Send + Sync> { format!("{e}").into() })?;
     let (decoded, _) = bincode::decode_from_slice(&packet, bincode::config::standard())?;
     Ok(decoded)
 }
@@ -184,4 +597,76 @@ mod tests {
 
         assert_eq!(test_struct, result);
     }
+
+    #[test]
+    fn test_recv_packet_with_rejects_oversized_length() {
+        let (mut reader, mut writer) = pipe().unwrap();
+
+        // A length prefix claiming a 1 KiB body, with a config that only allows 8 bytes.
+        writer.write_all(&1024u32.to_be_bytes()).unwrap();
+        drop(writer);
+
+        let config = PacketConfig::new(8);
+        let result: Result<TestStruct, _> = recv_packet_with(&mut reader, config);
+
+        match result {
+            Err(err) => {
+                let packet_err = err.downcast_ref::<PacketError>().expect("expected PacketError");
+                match packet_err {
+                    PacketError::PacketTooLarge { len, max } => {
+                        assert_eq!(*len, 1024);
+                        assert_eq!(*max, 8);
+                    }
+                    other => panic!("expected PacketTooLarge, got {other:?}"),
+                }
+            }
+            Ok(_) => panic!("expected PacketTooLarge error"),
+        }
+    }
+
+    #[test]
+    fn test_send_recv_packet_with_compression_round_trip() {
+        let (mut reader, mut writer) = pipe().unwrap();
+
+        let test_struct = TestStruct {
+            field1: 1,
+            field2: 2,
+        };
+
+        // A tiny threshold forces compression even for this small payload.
+        let config = PacketConfig::default().with_compression(Compression::Deflate, 0);
+
+        send_packet_with(&test_struct, &mut writer, config).unwrap();
+        drop(writer);
+
+        let result: TestStruct = recv_packet_with(&mut reader, config).unwrap();
+
+        assert_eq!(result, test_struct);
+    }
+
+    #[test]
+    fn test_recv_packet_with_rejects_oversized_decompressed_body() {
+        let (mut reader, mut writer) = pipe().unwrap();
+
+        // A highly compressible payload that's tiny on the wire but expands
+        // to far more than the configured max_len once decompressed.
+        let huge: Vec<u8> = vec![0u8; 1024 * 1024];
+        let compress_config = PacketConfig::default().with_compression(Compression::Zstd, 0);
+        send_packet_with(&huge, &mut writer, compress_config).unwrap();
+        drop(writer);
+
+        let config = PacketConfig::new(1024).with_compression(Compression::Zstd, 0);
+        let result: Result<Vec<u8>, _> = recv_packet_with(&mut reader, config);
+
+        match result {
+            Err(err) => {
+                let packet_err = err.downcast_ref::<PacketError>().expect("expected PacketError");
+                match packet_err {
+                    PacketError::DecompressedPacketTooLarge { max } => assert_eq!(*max, 1024),
+                    other => panic!("expected DecompressedPacketTooLarge, got {other:?}"),
+                }
+            }
+            Ok(_) => panic!("expected DecompressedPacketTooLarge error"),
+        }
+    }
 }
@@ -0,0 +1,153 @@
+//! Declarative macros for defining a protocol's packet set without
+//! hand-writing `Encode`/`Decode` wiring or id-to-type match arms.
+//!
+//! [`define_packet!`] generates a struct plus the usual derives; then
+//! [`protocol_state!`] maps each packet type to a one-byte id and generates
+//! an enum with `id()`, `write()` and `recv_packet()` so the id↔type mapping
+//! lives in exactly one place. On the wire this pairs that one-byte id with
+//! the existing length-prefixed bincode body, so [`protocol_state!`]'s
+//! generated reader can read the id, then defer to the ordinary
+//! [`crate::recv_packet`] framing for the body.
+//!
+//! ```ignore
+//! define_packet!(Ping { nonce: u64 });
+//! define_packet!(Pong { nonce: u64 });
+//!
+//! protocol_state! {
+//!     ProtocolPacket {
+//!         0 => Ping(Ping),
+//!         1 => Pong(Pong),
+//!     }
+//! }
+//!
+//! let packet = ProtocolPacket::Ping(Ping { nonce: 1 });
+//! packet.write(&mut writer)?;
+//! let received = ProtocolPacket::recv_packet(&mut reader)?;
+//! ```
+
+/// Declares a packet struct deriving `bincode::Encode`/`Decode` plus the
+/// usual `Debug`/`PartialEq`, matching the derives `packetio` itself uses
+/// for wire types.
+#[macro_export]
+macro_rules! define_packet {
+    ($name:ident { $($field:ident : $ty:ty),* $(,)? }) => {
+        #[derive(::bincode::Encode, ::bincode::Decode, Debug, PartialEq)]
+        pub struct $name {
+            $(pub $field: $ty),*
+        }
+    };
+}
+
+/// Maps `u8` packet ids to packet types and generates an enum dispatcher.
+///
+/// The generated `$enum_name` has one variant per mapping, plus:
+/// - `id(&self) -> u8`: the variant's wire id.
+/// - `write<W: Write>(&self, writer: &mut W)`: writes the id byte followed
+///   by the packet via [`crate::send_packet`].
+/// - `recv_packet<R: Read>(reader: &mut R) -> Result<Self, _>`: reads the id
+///   byte, then the packet via [`crate::recv_packet`], returning
+///   [`crate::PacketError::UnknownPacketId`] for an unmapped id.
+/// - `read_packet(id: u8, bytes: &[u8]) -> Result<Self, _>`: decodes an
+///   already-read id and raw bincode body directly, for callers that
+///   demultiplex ids themselves (e.g. alongside [`crate::framed`]).
+#[macro_export]
+macro_rules! protocol_state {
+    ($enum_name:ident { $($id:literal => $variant:ident ($ty:ty)),* $(,)? }) => {
+        #[derive(Debug)]
+        pub enum $enum_name {
+            $($variant($ty)),*
+        }
+
+        impl $enum_name {
+            pub fn id(&self) -> u8 {
+                match self {
+                    $(Self::$variant(_) => $id),*
+                }
+            }
+
+            pub fn write<W: ::std::io::Write>(&self, writer: &mut W) -> Result<(), Box<dyn ::std::error::Error>> {
+                match self {
+                    $(Self::$variant(packet) => {
+                        writer.write_all(&[$id])?;
+                        $crate::send_packet(packet, writer)
+                    }),*
+                }
+            }
+
+            pub fn recv_packet<R: ::std::io::Read>(reader: &mut R) -> Result<Self, Box<dyn ::std::error::Error>> {
+                let mut id_byte = [0u8; 1];
+                reader.read_exact(&mut id_byte)?;
+                match id_byte[0] {
+                    $($id => Ok(Self::$variant($crate::recv_packet(reader)?)),)*
+                    other => Err(Box::new($crate::PacketError::UnknownPacketId(other))),
+                }
+            }
+
+            pub fn read_packet(id: u8, bytes: &[u8]) -> Result<Self, Box<dyn ::std::error::Error>> {
+                match id {
+                    $($id => {
+                        let (decoded, _) = ::bincode::decode_from_slice(bytes, ::bincode::config::standard())?;
+                        Ok(Self::$variant(decoded))
+                    }),*
+                    other => Err(Box::new($crate::PacketError::UnknownPacketId(other))),
+                }
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use os_pipe::pipe;
+
+    define_packet!(Ping { nonce: u64 });
+    define_packet!(Pong { nonce: u64 });
+
+    protocol_state! {
+        ProtocolPacket {
+            0 => Ping(Ping),
+            1 => Pong(Pong),
+        }
+    }
+
+    #[test]
+    fn test_protocol_packet_write_recv_round_trip() {
+        let (mut reader, mut writer) = pipe().unwrap();
+
+        let packet = ProtocolPacket::Ping(Ping { nonce: 7 });
+        assert_eq!(packet.id(), 0);
+        packet.write(&mut writer).unwrap();
+        drop(writer);
+
+        let received = ProtocolPacket::recv_packet(&mut reader).unwrap();
+
+        match received {
+            ProtocolPacket::Ping(Ping { nonce }) => assert_eq!(nonce, 7),
+            ProtocolPacket::Pong(_) => panic!("expected Ping"),
+        }
+    }
+
+    #[test]
+    fn test_protocol_packet_read_packet_dispatches_by_id() {
+        let encoded = bincode::encode_to_vec(&Pong { nonce: 9 }, bincode::config::standard()).unwrap();
+
+        let decoded = ProtocolPacket::read_packet(1, &encoded).unwrap();
+
+        match decoded {
+            ProtocolPacket::Pong(Pong { nonce }) => assert_eq!(nonce, 9),
+            ProtocolPacket::Ping(_) => panic!("expected Pong"),
+        }
+    }
+
+    #[test]
+    fn test_protocol_packet_recv_rejects_unknown_id() {
+        let (mut reader, mut writer) = pipe().unwrap();
+
+        use std::io::Write;
+        writer.write_all(&[255]).unwrap();
+        drop(writer);
+
+        let result = ProtocolPacket::recv_packet(&mut reader);
+        assert!(result.is_err());
+    }
+}
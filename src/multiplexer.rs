@@ -0,0 +1,384 @@
+//! A prioritized, chunked multiplexer for running many outstanding requests
+//! over one `AsyncRead + AsyncWrite` stream.
+//!
+//! Each logical message is tagged with a [`RequestId`] and a [`Priority`]
+//! and split into chunks of at most [`MAX_CHUNK_SIZE`]. [`Multiplexer`]
+//! keeps one send queue ordered by priority then request id, so draining it
+//! one chunk at a time interleaves a large background transfer with small
+//! high-priority messages instead of letting the former starve the latter.
+//! The receive side reassembles chunks per request id until it sees the
+//! end-of-stream chunk, then the caller decodes the completed buffer.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    error::Error,
+};
+
+use bincode::{Decode, Encode};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{PacketError, DEFAULT_MAX_PACKET_LEN};
+
+/// Identifies one logical message (and its response) across a shared stream.
+pub type RequestId = u16;
+
+/// Maximum payload carried by a single chunk frame: 16 KiB.
+pub const MAX_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Default cap on the total bytes [`Multiplexer::recv_one_chunk`] will
+/// accumulate for one request id across repeated chunks before its
+/// end-of-stream chunk arrives. Shares [`DEFAULT_MAX_PACKET_LEN`] since both
+/// bound the same thing: one decoded message's size.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = DEFAULT_MAX_PACKET_LEN;
+
+/// Relative scheduling priority for a request's chunks.
+///
+/// Declared low-to-high so the derived [`Ord`] lets [`Multiplexer`] pick the
+/// highest-priority non-empty queue with `BTreeMap::iter().next_back()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Priority {
+    Background = 0,
+    Normal = 1,
+    High = 2,
+}
+
+impl Priority {
+    fn from_u8(value: u8) -> Result<Self, Box<dyn Error>> {
+        match value {
+            0 => Ok(Priority::Background),
+            1 => Ok(Priority::Normal),
+            2 => Ok(Priority::High),
+            other => Err(Box::new(PacketError::UnknownPriority(other))),
+        }
+    }
+}
+
+const CHUNK_HEADER_LEN: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ChunkHeader {
+    request_id: RequestId,
+    priority: Priority,
+    eos: bool,
+    length: u32,
+}
+
+impl ChunkHeader {
+    fn to_bytes(self) -> [u8; CHUNK_HEADER_LEN] {
+        let mut bytes = [0u8; CHUNK_HEADER_LEN];
+        bytes[0..2].copy_from_slice(&self.request_id.to_be_bytes());
+        bytes[2] = self.priority as u8;
+        bytes[3] = self.eos as u8;
+        bytes[4..8].copy_from_slice(&self.length.to_be_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: [u8; CHUNK_HEADER_LEN]) -> Result<Self, Box<dyn Error>> {
+        let request_id = u16::from_be_bytes(bytes[0..2].try_into().unwrap());
+        let priority = Priority::from_u8(bytes[2])?;
+        let eos = bytes[3] != 0;
+        let length = u32::from_be_bytes(bytes[4..8].try_into().unwrap());
+        Ok(Self {
+            request_id,
+            priority,
+            eos,
+            length,
+        })
+    }
+}
+
+/// Multiplexes many prioritized, chunked requests over one stream.
+///
+/// Senders call [`Multiplexer::enqueue`] then drain the stream with repeated
+/// [`Multiplexer::send_one_chunk`] calls (e.g. from a dedicated write-loop
+/// task); receivers call [`Multiplexer::recv_one_chunk`] in a loop and
+/// decode with [`Multiplexer::take_completed`] once it reports a request id
+/// as done.
+pub struct Multiplexer<S> {
+    stream: S,
+    send_queue: BTreeMap<(Priority, Reverse<RequestId>), VecDeque<Vec<u8>>>,
+    recv_buffers: HashMap<RequestId, Vec<u8>>,
+    closed: HashSet<RequestId>,
+    max_message_len: usize,
+}
+
+impl<S> Multiplexer<S> {
+    /// Creates a multiplexer that bounds each request id's reassembled
+    /// message to [`DEFAULT_MAX_MESSAGE_LEN`]. See
+    /// [`Multiplexer::with_max_message_len`] for a version that accepts a
+    /// custom limit.
+    pub fn new(stream: S) -> Self {
+        Self::with_max_message_len(stream, DEFAULT_MAX_MESSAGE_LEN)
+    }
+
+    /// Creates a multiplexer that rejects a request id's reassembled
+    /// message once it accumulates more than `max_message_len` bytes across
+    /// chunks, rather than letting a peer that never sets the end-of-stream
+    /// flag grow `recv_buffers` without bound.
+    pub fn with_max_message_len(stream: S, max_message_len: usize) -> Self {
+        Self {
+            stream,
+            send_queue: BTreeMap::new(),
+            recv_buffers: HashMap::new(),
+            closed: HashSet::new(),
+            max_message_len,
+        }
+    }
+
+    /// Encodes `packet` and splits it into `MAX_CHUNK_SIZE` chunks queued
+    /// under `request_id`/`priority`. An empty encoded payload still queues
+    /// one zero-length chunk, so the end-of-stream flag always reaches the
+    /// peer even for empty messages.
+    pub fn enqueue<T: Encode>(
+        &mut self,
+        request_id: RequestId,
+        priority: Priority,
+        packet: T,
+    ) -> Result<(), Box<dyn Error>> {
+        let encoded = bincode::encode_to_vec(&packet, bincode::config::standard())?;
+
+        let mut chunks: VecDeque<Vec<u8>> = if encoded.is_empty() {
+            VecDeque::from([Vec::new()])
+        } else {
+            encoded.chunks(MAX_CHUNK_SIZE).map(|c| c.to_vec()).collect()
+        };
+
+        self.send_queue
+            .entry((priority, Reverse(request_id)))
+            .or_default()
+            .append(&mut chunks);
+        Ok(())
+    }
+}
+
+impl<S: AsyncWrite + Unpin> Multiplexer<S> {
+    /// Writes one queued chunk from the highest-priority non-empty queue
+    /// (ties broken in favor of the lower request id). Returns `false` if
+    /// nothing is queued.
+    ///
+    /// The end-of-stream flag is only set once a request's queue is fully
+    /// drained, so a message split across many chunks never reports EOS
+    /// early regardless of how it divides by `MAX_CHUNK_SIZE`.
+    pub async fn send_one_chunk(&mut self) -> Result<bool, Box<dyn Error>> {
+        let Some(key) = self.send_queue.keys().next_back().copied() else {
+            return Ok(false);
+        };
+        let (priority, Reverse(request_id)) = key;
+
+        let queue = self.send_queue.get_mut(&key).expect("key was just read from the map");
+        let chunk = queue.pop_front().expect("queues are never left empty in the map");
+        let eos = queue.is_empty();
+        if eos {
+            self.send_queue.remove(&key);
+        }
+
+        let header = ChunkHeader {
+            request_id,
+            priority,
+            eos,
+            length: chunk.len() as u32,
+        };
+        self.stream.write_all(&header.to_bytes()).await?;
+        self.stream.write_all(&chunk).await?;
+        Ok(true)
+    }
+}
+
+impl<S: AsyncRead + Unpin> Multiplexer<S> {
+    /// Reads and buffers one chunk frame. Returns the request id whose
+    /// message just completed once its end-of-stream chunk arrives, so the
+    /// caller can decode it with [`Multiplexer::take_completed`].
+    pub async fn recv_one_chunk(&mut self) -> Result<Option<RequestId>, Box<dyn Error>> {
+        let mut header_bytes = [0u8; CHUNK_HEADER_LEN];
+        self.stream.read_exact(&mut header_bytes).await?;
+        let header = ChunkHeader::from_bytes(header_bytes)?;
+
+        if header.length as usize > MAX_CHUNK_SIZE {
+            return Err(Box::new(PacketError::PacketTooLarge {
+                len: header.length as usize,
+                max: MAX_CHUNK_SIZE,
+            }));
+        }
+
+        let mut payload = vec![0u8; header.length as usize];
+        self.stream.read_exact(&mut payload).await?;
+
+        if self.closed.contains(&header.request_id) {
+            // Closed request id: drop the chunk cleanly instead of buffering it.
+            // An id never seen before is not dropped: its first chunk starts a new buffer.
+            if header.eos {
+                self.closed.remove(&header.request_id);
+            }
+            return Ok(None);
+        }
+
+        let buffer = self.recv_buffers.entry(header.request_id).or_default();
+        buffer.extend_from_slice(&payload);
+
+        if buffer.len() > self.max_message_len {
+            let len = buffer.len();
+            self.recv_buffers.remove(&header.request_id);
+            return Err(Box::new(PacketError::PacketTooLarge {
+                len,
+                max: self.max_message_len,
+            }));
+        }
+
+        Ok(header.eos.then_some(header.request_id))
+    }
+
+    /// Takes and decodes the buffer completed for `request_id`, as
+    /// signalled by [`Multiplexer::recv_one_chunk`] returning `Some`.
+    pub fn take_completed<T: Decode<()>>(&mut self, request_id: RequestId) -> Result<T, Box<dyn Error>> {
+        let buffer = self.recv_buffers.remove(&request_id).unwrap_or_default();
+        let (decoded, _) = bincode::decode_from_slice(&buffer, bincode::config::standard())?;
+        Ok(decoded)
+    }
+
+    /// Marks `request_id` as closed: further chunks for it are dropped
+    /// cleanly until its sender's end-of-stream chunk arrives, at which
+    /// point the id is forgotten and can be reused.
+    pub fn close_request(&mut self, request_id: RequestId) {
+        self.recv_buffers.remove(&request_id);
+        self.closed.insert(request_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_header_round_trip() {
+        let header = ChunkHeader {
+            request_id: 42,
+            priority: Priority::High,
+            eos: true,
+            length: 128,
+        };
+
+        let decoded = ChunkHeader::from_bytes(header.to_bytes()).unwrap();
+
+        assert_eq!(header, decoded);
+    }
+
+    #[test]
+    fn test_priority_ordering_picks_high_before_background() {
+        assert!(Priority::High > Priority::Normal);
+        assert!(Priority::Normal > Priority::Background);
+    }
+
+    #[test]
+    fn test_enqueue_empty_payload_still_queues_one_eos_chunk() {
+        let mut mux = Multiplexer::new(Vec::<u8>::new());
+        mux.enqueue(1, Priority::Normal, ()).unwrap();
+
+        let queue = mux
+            .send_queue
+            .get(&(Priority::Normal, Reverse(1)))
+            .expect("queue should exist for request 1");
+
+        assert_eq!(queue.len(), 1);
+        assert!(queue[0].is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_multiplexer_end_to_end_priority_and_reassembly() {
+        let (client, server) = tokio::io::duplex(1024 * 1024);
+        let mut sender = Multiplexer::new(client);
+        let mut receiver = Multiplexer::new(server);
+
+        // A background transfer spanning several chunks, whose size doesn't
+        // divide evenly by MAX_CHUNK_SIZE, to exercise the chunk-count edge
+        // case at the stream boundary.
+        let background_payload = vec![7u8; MAX_CHUNK_SIZE * 2 + 5];
+        sender
+            .enqueue(10, Priority::Background, background_payload.clone())
+            .unwrap();
+        // Enqueued after the background transfer, but High priority, so it
+        // must still be scheduled first.
+        sender.enqueue(20, Priority::High, vec![1u8, 2, 3]).unwrap();
+
+        while sender.send_one_chunk().await.unwrap() {}
+        drop(sender);
+
+        let mut completed = Vec::new();
+        while completed.len() < 2 {
+            if let Some(id) = receiver.recv_one_chunk().await.unwrap() {
+                completed.push(id);
+            }
+        }
+
+        assert_eq!(
+            completed,
+            vec![20, 10],
+            "the High priority request must finish before the larger Background one"
+        );
+
+        let high: Vec<u8> = receiver.take_completed(20).unwrap();
+        assert_eq!(high, vec![1u8, 2, 3]);
+
+        let background: Vec<u8> = receiver.take_completed(10).unwrap();
+        assert_eq!(background, background_payload);
+    }
+
+    #[tokio::test]
+    async fn test_multiplexer_drops_chunks_for_closed_request_id() {
+        let (client, server) = tokio::io::duplex(1024 * 1024);
+        let mut sender = Multiplexer::new(client);
+        let mut receiver = Multiplexer::new(server);
+
+        receiver.close_request(99);
+
+        // Lower request id is scheduled first within the same priority.
+        sender.enqueue(1, Priority::Normal, vec![9u8]).unwrap();
+        sender.enqueue(99, Priority::Normal, vec![1u8, 2, 3]).unwrap();
+
+        while sender.send_one_chunk().await.unwrap() {}
+        drop(sender);
+
+        let first = receiver.recv_one_chunk().await.unwrap();
+        assert_eq!(first, Some(1));
+        let decoded: Vec<u8> = receiver.take_completed(1).unwrap();
+        assert_eq!(decoded, vec![9u8]);
+
+        let second = receiver.recv_one_chunk().await.unwrap();
+        assert_eq!(second, None, "closed request id's chunk must be dropped, not completed");
+        assert!(!receiver.recv_buffers.contains_key(&99), "closed request id must never be buffered");
+    }
+
+    #[tokio::test]
+    async fn test_multiplexer_rejects_accumulated_message_over_max_len() {
+        let (client, server) = tokio::io::duplex(1024 * 1024);
+        let mut sender = Multiplexer::new(client);
+        let mut receiver = Multiplexer::with_max_message_len(server, MAX_CHUNK_SIZE);
+
+        // Both payloads share request 1's queue and encode to 3 + MAX_CHUNK_SIZE
+        // bytes each, so four chunks go out, none over MAX_CHUNK_SIZE and only
+        // the last marked eos. The accumulated total crosses the cap on the
+        // second chunk, well before eos, so it must still be bounded.
+        sender
+            .enqueue(1, Priority::Normal, vec![1u8; MAX_CHUNK_SIZE])
+            .unwrap();
+        sender
+            .enqueue(1, Priority::Normal, vec![2u8; MAX_CHUNK_SIZE])
+            .unwrap();
+
+        while sender.send_one_chunk().await.unwrap() {}
+        drop(sender);
+
+        receiver.recv_one_chunk().await.unwrap();
+        let err = receiver.recv_one_chunk().await.unwrap_err();
+        let packet_err = err.downcast_ref::<PacketError>().expect("expected PacketError");
+        assert!(matches!(
+            packet_err,
+            PacketError::PacketTooLarge { max, .. } if *max == MAX_CHUNK_SIZE
+        ));
+        assert!(
+            !receiver.recv_buffers.contains_key(&1),
+            "oversized accumulated message must not stay buffered"
+        );
+    }
+}
@@ -0,0 +1,355 @@
+//! Streaming readers/writers that expose a packet's body as a bounded
+//! `Read`/`Write` (or `AsyncRead`/`AsyncWrite`) instead of buffering the
+//! whole thing into a `Vec` up front.
+//!
+//! [`PacketReader`] reads the length prefix, then lets the caller pull
+//! exactly that many bytes straight into a file, hasher, or their own
+//! decoder, returning EOF at the frame boundary. [`PacketWriter`] is the
+//! send-side counterpart: it takes a declared length up front and enforces
+//! that exactly that many bytes are written. Call [`PacketReader::finish`] /
+//! [`PacketWriter::finish`] once done, which errors if the stream was left
+//! misaligned (bytes unread, or the wrong number of bytes written).
+
+use std::{
+    error::Error,
+    io::{self, Read, Write},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::{PacketConfig, PacketError};
+
+/// Reads a packet's length prefix, then exposes exactly that many bytes of
+/// body as a bounded [`Read`].
+pub struct PacketReader<'r, R> {
+    reader: &'r mut R,
+    remaining: usize,
+}
+
+impl<'r, R: Read> PacketReader<'r, R> {
+    /// Reads the length prefix using [`PacketConfig::default`]. See
+    /// [`PacketReader::with_config`] for a version that accepts a custom
+    /// size limit.
+    pub fn new(reader: &'r mut R) -> Result<Self, Box<dyn Error>> {
+        Self::with_config(reader, PacketConfig::default())
+    }
+
+    /// Reads the length prefix, rejecting it with
+    /// [`PacketError::PacketTooLarge`] if it exceeds `config.max_len`.
+    pub fn with_config(reader: &'r mut R, config: PacketConfig) -> Result<Self, Box<dyn Error>> {
+        let mut len_bytes = [0u8; 4];
+        reader.read_exact(&mut len_bytes)?;
+        let len = u32::from_be_bytes(len_bytes) as usize;
+
+        if len > config.max_len {
+            return Err(Box::new(PacketError::PacketTooLarge {
+                len,
+                max: config.max_len,
+            }));
+        }
+
+        Ok(Self { reader, remaining: len })
+    }
+
+    /// Bytes of the packet body not yet read.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Consumes the reader, erroring with [`PacketError::IncompleteRead`] if
+    /// any of the declared body was left unread. Call this once done so a
+    /// short read doesn't leave the underlying stream misaligned for the
+    /// next packet.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        if self.remaining != 0 {
+            return Err(Box::new(PacketError::IncompleteRead {
+                remaining: self.remaining,
+            }));
+        }
+        Ok(())
+    }
+}
+
+impl<'r, R: Read> Read for PacketReader<'r, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let max = buf.len().min(self.remaining);
+        let n = self.reader.read(&mut buf[..max])?;
+        self.remaining -= n;
+        Ok(n)
+    }
+}
+
+/// Takes a declared body length up front, then exposes a bounded [`Write`]
+/// that enforces exactly that many bytes are written before the length
+/// prefix can be considered honest.
+pub struct PacketWriter<'w, W> {
+    writer: &'w mut W,
+    declared_len: usize,
+    written: usize,
+}
+
+impl<'w, W: Write> PacketWriter<'w, W> {
+    /// Writes the length prefix for a body of `declared_len` bytes.
+    ///
+    /// Errors with [`PacketError::DeclaredLenTooLarge`] if `declared_len`
+    /// doesn't fit in the `u32` wire length prefix, before writing anything.
+    pub fn new(writer: &'w mut W, declared_len: usize) -> Result<Self, Box<dyn Error>> {
+        let declared_len_u32: u32 = declared_len
+            .try_into()
+            .map_err(|_| PacketError::DeclaredLenTooLarge { declared_len })?;
+        writer.write_all(&declared_len_u32.to_be_bytes())?;
+        Ok(Self {
+            writer,
+            declared_len,
+            written: 0,
+        })
+    }
+
+    /// Consumes the writer, erroring with [`PacketError::IncompleteWrite`]
+    /// unless exactly `declared_len` bytes were written.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        if self.written != self.declared_len {
+            return Err(Box::new(PacketError::IncompleteWrite {
+                written: self.written,
+                declared: self.declared_len,
+            }));
+        }
+        Ok(())
+    }
+}
+
+impl<'w, W: Write> Write for PacketWriter<'w, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let remaining = self.declared_len - self.written;
+        if buf.len() > remaining {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "write exceeds declared packet length",
+            ));
+        }
+        let n = self.writer.write(buf)?;
+        self.written += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Async counterpart to [`PacketReader`]. See its docs for usage.
+pub struct AsyncPacketReader<'r, R> {
+    reader: &'r mut R,
+    remaining: usize,
+}
+
+impl<'r, R: AsyncRead + Unpin> AsyncPacketReader<'r, R> {
+    pub async fn new(reader: &'r mut R) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        Self::with_config(reader, PacketConfig::default()).await
+    }
+
+    pub async fn with_config(
+        reader: &'r mut R,
+        config: PacketConfig,
+    ) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let mut len_bytes = [0u8; 4];
+        reader.read_exact(&mut len_bytes).await?;
+        let len = u32::from_be_bytes(len_bytes) as usize;
+
+        if len > config.max_len {
+            return Err(Box::new(PacketError::PacketTooLarge {
+                len,
+                max: config.max_len,
+            }));
+        }
+
+        Ok(Self { reader, remaining: len })
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.remaining != 0 {
+            return Err(Box::new(PacketError::IncompleteRead {
+                remaining: self.remaining,
+            }));
+        }
+        Ok(())
+    }
+}
+
+impl<'r, R: AsyncRead + Unpin> AsyncRead for AsyncPacketReader<'r, R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.remaining == 0 {
+            return Poll::Ready(Ok(()));
+        }
+
+        let max = this.remaining.min(buf.remaining());
+        let mut limited = buf.take(max);
+        let before = limited.filled().len();
+
+        match Pin::new(&mut *this.reader).poll_read(cx, &mut limited) {
+            Poll::Ready(Ok(())) => {
+                let n = limited.filled().len() - before;
+                // SAFETY: the inner reader just filled these `n` bytes of
+                // `buf`'s unfilled region through `limited`.
+                unsafe { buf.assume_init(n) };
+                buf.advance(n);
+                this.remaining -= n;
+                Poll::Ready(Ok(()))
+            }
+            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Async counterpart to [`PacketWriter`]. See its docs for usage.
+pub struct AsyncPacketWriter<'w, W> {
+    writer: &'w mut W,
+    declared_len: usize,
+    written: usize,
+}
+
+impl<'w, W: AsyncWrite + Unpin> AsyncPacketWriter<'w, W> {
+    /// Writes the length prefix for a body of `declared_len` bytes.
+    ///
+    /// Errors with [`PacketError::DeclaredLenTooLarge`] if `declared_len`
+    /// doesn't fit in the `u32` wire length prefix, before writing anything.
+    pub async fn new(writer: &'w mut W, declared_len: usize) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let declared_len_u32: u32 = declared_len
+            .try_into()
+            .map_err(|_| PacketError::DeclaredLenTooLarge { declared_len })?;
+        writer.write_all(&declared_len_u32.to_be_bytes()).await?;
+        Ok(Self {
+            writer,
+            declared_len,
+            written: 0,
+        })
+    }
+
+    pub fn finish(self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.written != self.declared_len {
+            return Err(Box::new(PacketError::IncompleteWrite {
+                written: self.written,
+                declared: self.declared_len,
+            }));
+        }
+        Ok(())
+    }
+}
+
+impl<'w, W: AsyncWrite + Unpin> AsyncWrite for AsyncPacketWriter<'w, W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let remaining = this.declared_len - this.written;
+        if buf.len() > remaining {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "write exceeds declared packet length",
+            )));
+        }
+
+        match Pin::new(&mut *this.writer).poll_write(cx, buf) {
+            Poll::Ready(Ok(n)) => {
+                this.written += n;
+                Poll::Ready(Ok(n))
+            }
+            other => other,
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut *self.get_mut().writer).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use os_pipe::pipe;
+
+    #[test]
+    fn test_packet_reader_writer_round_trip() {
+        let (mut reader, mut writer) = pipe().unwrap();
+
+        let body = b"hello streaming world";
+        {
+            let mut packet_writer = PacketWriter::new(&mut writer, body.len()).unwrap();
+            packet_writer.write_all(body).unwrap();
+            packet_writer.finish().unwrap();
+        }
+        drop(writer);
+
+        let mut packet_reader = PacketReader::new(&mut reader).unwrap();
+        let mut out = Vec::new();
+        packet_reader.read_to_end(&mut out).unwrap();
+        packet_reader.finish().unwrap();
+
+        assert_eq!(out, body);
+    }
+
+    #[test]
+    fn test_packet_reader_finish_errors_on_unread_bytes() {
+        let (mut reader, mut writer) = pipe().unwrap();
+
+        writer.write_all(&4u32.to_be_bytes()).unwrap();
+        writer.write_all(b"abcd").unwrap();
+        drop(writer);
+
+        let mut packet_reader = PacketReader::new(&mut reader).unwrap();
+        let mut one_byte = [0u8; 1];
+        packet_reader.read_exact(&mut one_byte).unwrap();
+
+        let err = packet_reader.finish().unwrap_err();
+        let packet_err = err.downcast_ref::<PacketError>().expect("expected PacketError");
+        assert!(matches!(packet_err, PacketError::IncompleteRead { remaining: 3 }));
+    }
+
+    #[test]
+    fn test_packet_writer_finish_errors_on_short_write() {
+        let (_reader, mut writer) = pipe().unwrap();
+
+        let mut packet_writer = PacketWriter::new(&mut writer, 4).unwrap();
+        packet_writer.write_all(b"ab").unwrap();
+
+        let err = packet_writer.finish().unwrap_err();
+        let packet_err = err.downcast_ref::<PacketError>().expect("expected PacketError");
+        assert!(matches!(
+            packet_err,
+            PacketError::IncompleteWrite { written: 2, declared: 4 }
+        ));
+    }
+
+    #[test]
+    fn test_packet_writer_new_rejects_declared_len_over_u32_max() {
+        let (_reader, mut writer) = pipe().unwrap();
+
+        let declared_len = u32::MAX as usize + 1;
+        let err = match PacketWriter::new(&mut writer, declared_len) {
+            Ok(_) => panic!("expected DeclaredLenTooLarge error"),
+            Err(err) => err,
+        };
+        let packet_err = err.downcast_ref::<PacketError>().expect("expected PacketError");
+        assert!(matches!(
+            packet_err,
+            PacketError::DeclaredLenTooLarge { declared_len: d } if *d == declared_len
+        ));
+    }
+}